@@ -1,12 +1,566 @@
-use std::sync::Mutex;
-use tauri::State;
+use shared_child::SharedChild;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, Manager, State};
 
-pub struct SidecarState {
-    pub port: Option<u16>,
+/// Initial delay before the first auto-restart attempt after a crash.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+/// Ceiling the backoff doubles up to.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Consecutive crashes tolerated before giving up and emitting `sidecar://failed`.
+const MAX_RETRIES: u32 = 8;
+/// How long the sidecar has to stay up before a crash resets the backoff.
+const HEALTHY_UPTIME: Duration = Duration::from_secs(10);
+/// How often the supervisor polls the child for exit.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long to wait for the startup handshake before giving up.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(15);
+/// How many trailing stderr lines to keep around for startup-timeout diagnostics.
+const STDERR_TAIL_LINES: usize = 20;
+/// How long to wait for a clean exit on app shutdown before force-killing.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(3);
+/// How often to poll the child for exit during graceful shutdown.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Payload for the `sidecar://log` event, one per line of child output.
+#[derive(Clone, serde::Serialize)]
+struct SidecarLogEvent {
+    stream: &'static str,
+    line: String,
+    timestamp: u64,
+}
+
+fn emit_log_line(app_handle: &AppHandle, stream: &'static str, line: &str) {
+    let line = line.strip_suffix('\r').unwrap_or(line).to_string();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let _ = app_handle.emit("sidecar://log", SidecarLogEvent { stream, line, timestamp });
+}
+
+/// Payload for the `sidecar://crashed` event.
+#[derive(Clone, serde::Serialize)]
+struct SidecarCrashedEvent {
+    code: Option<i32>,
+}
+
+/// Payload for the `sidecar://failed` event, emitted once the retry budget
+/// is exhausted.
+#[derive(Clone, serde::Serialize)]
+struct SidecarFailedEvent {
+    attempts: u32,
+}
+
+/// The startup handshake line the sidecar prints to stdout once it's
+/// listening, e.g. `{"event":"ready","port":54231,"pid":1234}`.
+#[derive(serde::Deserialize)]
+struct ReadyMessage {
+    event: String,
+    port: u16,
+    pid: Option<u32>,
+}
+
+/// Payload for the `sidecar://ready` event.
+#[derive(Clone, serde::Serialize)]
+struct SidecarReadyEvent {
+    port: u16,
+    pid: Option<u32>,
+}
+
+/// Payload for the `sidecar://startup-timeout` event.
+#[derive(Clone, serde::Serialize)]
+struct SidecarStartupTimeoutEvent {
+    stderr_tail: Vec<String>,
+}
+
+/// Lifecycle phase of the Python sidecar process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SidecarPhase {
+    Stopped,
+    Starting,
+    Running,
+}
+
+/// Paths needed to spawn the sidecar, resolved once at startup.
+pub struct SidecarConfig {
+    pub python_path: PathBuf,
+    pub sidecar_dir: PathBuf,
+}
+
+/// Name of the venv's Python interpreter on this platform.
+fn venv_python_name() -> &'static str {
+    if cfg!(windows) {
+        "python.exe"
+    } else {
+        "python3"
+    }
+}
+
+/// Directory a venv keeps its interpreter in on this platform.
+fn venv_bin_dir() -> &'static str {
+    if cfg!(windows) {
+        "Scripts"
+    } else {
+        "bin"
+    }
+}
+
+/// Resolves where the sidecar lives. In debug builds this is always the dev
+/// venv next to the project root; in release builds it's the bundled copy
+/// under the app's resource directory. Never panics — callers get a `String`
+/// describing what's missing so the app can surface it instead of dying.
+pub fn resolve_sidecar_config(_app: &tauri::App) -> Result<SidecarConfig, String> {
+    #[cfg(debug_assertions)]
+    {
+        let project_root = std::env::current_dir()
+            .map_err(|e| format!("Failed to get current directory: {e}"))?
+            .parent()
+            .ok_or_else(|| "Failed to resolve project root from current directory".to_string())?
+            .to_path_buf();
+
+        let sidecar_dir = project_root.join("sidecar");
+        let python_path = sidecar_dir
+            .join(".venv")
+            .join(venv_bin_dir())
+            .join(venv_python_name());
+
+        if !python_path.exists() {
+            return Err(format!(
+                "Dev sidecar interpreter not found at {}; run the sidecar venv setup first",
+                python_path.display()
+            ));
+        }
+
+        Ok(SidecarConfig {
+            python_path,
+            sidecar_dir,
+        })
+    }
+
+    #[cfg(not(debug_assertions))]
+    {
+        let resource_dir = _app
+            .path()
+            .resource_dir()
+            .map_err(|e| format!("Failed to resolve bundled resource directory: {e}"))?;
+
+        let sidecar_dir = resource_dir.join("sidecar");
+        let python_path = sidecar_dir
+            .join(".venv")
+            .join(venv_bin_dir())
+            .join(venv_python_name());
+        let main_py = sidecar_dir.join("main.py");
+
+        if !python_path.exists() {
+            return Err(format!(
+                "Bundled sidecar interpreter missing at {}",
+                python_path.display()
+            ));
+        }
+        if !main_py.exists() {
+            return Err(format!(
+                "Bundled sidecar entry point missing at {}",
+                main_py.display()
+            ));
+        }
+
+        Ok(SidecarConfig {
+            python_path,
+            sidecar_dir,
+        })
+    }
+}
+
+struct SidecarState {
+    phase: SidecarPhase,
+    port: Option<u16>,
+    pid: Option<u32>,
+    child: Option<Arc<SharedChild>>,
+    stdin: Option<std::process::ChildStdin>,
+    /// Bumped on every manual start/stop so a stale supervisor thread can
+    /// tell it's been superseded and should stop treating exits as crashes.
+    generation: u64,
+}
+
+impl SidecarState {
+    fn new() -> Self {
+        Self {
+            phase: SidecarPhase::Stopped,
+            port: None,
+            pid: None,
+            child: None,
+            stdin: None,
+            generation: 0,
+        }
+    }
+}
+
+/// Owns the sidecar child process and mediates every transition between
+/// Stopped/Starting/Running, so the frontend never has to reach for the
+/// child handle directly.
+pub struct SidecarManager {
+    config: SidecarConfig,
+    state: Mutex<SidecarState>,
+}
+
+impl SidecarManager {
+    pub fn new(config: SidecarConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(SidecarState::new()),
+        }
+    }
+
+    /// A manager with no usable sidecar location, for when resolution fails
+    /// entirely. Stays `Stopped`; `start_sidecar` will surface the same
+    /// "file not found" error a user can act on instead of panicking.
+    pub fn unresolved() -> Self {
+        Self::new(SidecarConfig {
+            python_path: PathBuf::new(),
+            sidecar_dir: PathBuf::new(),
+        })
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.state.lock().unwrap().port
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        self.state.lock().unwrap().pid
+    }
+
+    /// Spawns the sidecar, starts its log reader threads, and hands it off
+    /// to a supervisor thread that auto-restarts it on an unexpected exit.
+    /// No-op if the sidecar is already starting or running.
+    pub fn start(&self, app_handle: &AppHandle) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        if state.phase != SidecarPhase::Stopped {
+            return Ok(());
+        }
+        state.phase = SidecarPhase::Starting;
+        state.generation += 1;
+        let generation = state.generation;
+        drop(state);
+
+        if let Err(err) = self.spawn_child(app_handle, generation) {
+            // Restore Stopped so a later start_sidecar isn't a permanent no-op.
+            let mut state = self.state.lock().unwrap();
+            if state.generation == generation {
+                state.phase = SidecarPhase::Stopped;
+            }
+            return Err(err);
+        }
+
+        std::thread::spawn({
+            let handle = app_handle.clone();
+            move || {
+                let manager = handle.state::<SidecarManager>();
+                manager.supervise(&handle, generation);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Spawns the Python process and its stdout/stderr reader threads,
+    /// storing the child under `generation` if it's still the current one.
+    fn spawn_child(&self, app_handle: &AppHandle, generation: u64) -> Result<(), String> {
+        let mut command = Command::new(&self.config.python_path);
+        command
+            .arg("-u") // unbuffered stdout
+            .arg("main.py")
+            .current_dir(&self.config.sidecar_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let child = SharedChild::spawn(&mut command)
+            .map_err(|e| format!("Failed to spawn Python sidecar: {e}"))?;
+        let child = Arc::new(child);
+
+        let stdin = child.take_stdin();
+        let stdout = child.take_stdout().expect("Failed to capture stdout");
+        let stderr = child.take_stderr().expect("Failed to capture stderr");
+
+        let ready_flag = Arc::new(AtomicBool::new(false));
+        let stderr_tail: Arc<Mutex<VecDeque<String>>> = Arc::new(Mutex::new(VecDeque::new()));
+
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.generation != generation {
+                // Superseded by a manual stop/restart while we were spawning.
+                let _ = child.kill();
+                return Ok(());
+            }
+            state.child = Some(child.clone());
+            state.stdin = stdin;
+            state.phase = SidecarPhase::Running;
+        }
+
+        std::thread::spawn({
+            let handle = app_handle.clone();
+            let ready_flag = ready_flag.clone();
+            move || {
+                let mut lines = BufReader::new(stdout).lines();
+                while let Some(line) = lines.next() {
+                    let Ok(line) = line else { break };
+                    if let Ok(ready) = serde_json::from_str::<ReadyMessage>(&line) {
+                        if ready.event == "ready" {
+                            let manager = handle.state::<SidecarManager>();
+                            let mut state = manager.state.lock().unwrap();
+                            if state.generation == generation {
+                                state.port = Some(ready.port);
+                                state.pid = ready.pid;
+                            }
+                            drop(state);
+                            ready_flag.store(true, Ordering::SeqCst);
+                            let _ = handle.emit(
+                                "sidecar://ready",
+                                SidecarReadyEvent {
+                                    port: ready.port,
+                                    pid: ready.pid,
+                                },
+                            );
+                            eprintln!("Sidecar ready on port {}", ready.port);
+                        }
+                    }
+                    emit_log_line(&handle, "stdout", &line);
+                }
+            }
+        });
+
+        std::thread::spawn({
+            let handle = app_handle.clone();
+            let stderr_tail = stderr_tail.clone();
+            move || {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Some(line) = lines.next() {
+                    let Ok(line) = line else { break };
+                    let mut tail = stderr_tail.lock().unwrap();
+                    if tail.len() >= STDERR_TAIL_LINES {
+                        tail.pop_front();
+                    }
+                    tail.push_back(line.clone());
+                    drop(tail);
+                    emit_log_line(&handle, "stderr", &line);
+                }
+            }
+        });
+
+        std::thread::spawn({
+            let handle = app_handle.clone();
+            let child = child.clone();
+            move || {
+                std::thread::sleep(STARTUP_TIMEOUT);
+                if ready_flag.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                let manager = handle.state::<SidecarManager>();
+                let mut state = manager.state.lock().unwrap();
+                if state.generation != generation {
+                    return;
+                }
+                // Bump the generation so the supervisor sees this kill as an
+                // intentional stop rather than a crash to auto-restart from.
+                state.generation += 1;
+                state.phase = SidecarPhase::Stopped;
+                state.child = None;
+                drop(state);
+
+                let _ = child.kill();
+                let tail: Vec<String> = stderr_tail.lock().unwrap().iter().cloned().collect();
+                let _ = handle.emit(
+                    "sidecar://startup-timeout",
+                    SidecarStartupTimeoutEvent { stderr_tail: tail },
+                );
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Polls the child started under `generation` and, if it exits
+    /// unexpectedly, respawns it with exponential backoff. Gives up once
+    /// `MAX_RETRIES` consecutive restarts have crashed too. Returns as soon as a manual
+    /// start/stop/restart bumps the generation out from under it.
+    fn supervise(&self, app_handle: &AppHandle, mut generation: u64) {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0u32;
+
+        loop {
+            let started_at = Instant::now();
+            let exit_status = loop {
+                std::thread::sleep(POLL_INTERVAL);
+                let child = {
+                    let state = self.state.lock().unwrap();
+                    if state.generation != generation {
+                        return;
+                    }
+                    state.child.clone()
+                };
+                let Some(child) = child else { return };
+                match child.try_wait() {
+                    Ok(Some(status)) => break Some(status),
+                    Ok(None) => continue,
+                    Err(_) => break None,
+                }
+            };
+
+            let mut state = self.state.lock().unwrap();
+            if state.generation != generation {
+                return;
+            }
+            state.child = None;
+            state.phase = SidecarPhase::Stopped;
+            state.port = None;
+            state.pid = None;
+            drop(state);
+
+            let _ = app_handle.emit(
+                "sidecar://crashed",
+                SidecarCrashedEvent {
+                    code: exit_status.and_then(|s| s.code()),
+                },
+            );
+
+            if started_at.elapsed() >= HEALTHY_UPTIME {
+                backoff = INITIAL_BACKOFF;
+                attempt = 0;
+            }
+
+            if attempt >= MAX_RETRIES {
+                let _ = app_handle.emit("sidecar://failed", SidecarFailedEvent { attempts: attempt });
+                return;
+            }
+
+            std::thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            attempt += 1;
+
+            let mut state = self.state.lock().unwrap();
+            if state.generation != generation {
+                return;
+            }
+            state.generation += 1;
+            generation = state.generation;
+            state.phase = SidecarPhase::Starting;
+            drop(state);
+
+            if self.spawn_child(app_handle, generation).is_err() {
+                // Restore Stopped so a later start_sidecar isn't a permanent no-op.
+                let mut state = self.state.lock().unwrap();
+                if state.generation == generation {
+                    state.phase = SidecarPhase::Stopped;
+                }
+                return;
+            }
+        }
+    }
+
+    /// Terminates the child, if any, and clears the stored port. Bumps the
+    /// generation first so a concurrently-running supervisor treats this as
+    /// an intentional stop rather than a crash.
+    pub fn stop(&self) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        state.generation += 1;
+        state.stdin = None;
+        if let Some(child) = state.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        state.phase = SidecarPhase::Stopped;
+        state.port = None;
+        state.pid = None;
+        Ok(())
+    }
+
+    /// Stops the sidecar (if running) and spawns a fresh one.
+    pub fn restart(&self, app_handle: &AppHandle) -> Result<(), String> {
+        self.stop()?;
+        self.start(app_handle)
+    }
+
+    /// Two-phase shutdown for app exit: ask the sidecar to stop cleanly
+    /// (SIGTERM on Unix, a `shutdown` line on stdin elsewhere), give it
+    /// `SHUTDOWN_GRACE_PERIOD` to exit on its own, and only then kill it.
+    pub fn shutdown(&self) {
+        let (child, stdin) = {
+            let mut state = self.state.lock().unwrap();
+            state.generation += 1;
+            let child = state.child.take();
+            let stdin = state.stdin.take();
+            state.phase = SidecarPhase::Stopped;
+            state.port = None;
+            state.pid = None;
+            (child, stdin)
+        };
+
+        let Some(child) = child else { return };
+        Self::request_graceful_stop(&child, stdin);
+
+        let deadline = Instant::now() + SHUTDOWN_GRACE_PERIOD;
+        loop {
+            match child.try_wait() {
+                Ok(Some(_)) | Err(_) => return,
+                Ok(None) => {}
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            std::thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+
+        eprintln!("Sidecar did not exit within the grace period; killing it");
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    fn request_graceful_stop(child: &SharedChild, stdin: Option<std::process::ChildStdin>) {
+        #[cfg(unix)]
+        {
+            use shared_child::unix::SharedChildExt;
+            let _ = child.send_signal(libc::SIGTERM);
+            let _ = stdin;
+        }
+        #[cfg(not(unix))]
+        {
+            use std::io::Write;
+            let _ = child;
+            if let Some(mut stdin) = stdin {
+                let _ = writeln!(stdin, "shutdown");
+            }
+        }
+    }
+}
+
+#[tauri::command]
+pub fn get_sidecar_port(manager: State<'_, SidecarManager>) -> Result<u16, String> {
+    manager.port().ok_or_else(|| "Sidecar not ready".to_string())
+}
+
+#[tauri::command]
+pub fn get_sidecar_pid(manager: State<'_, SidecarManager>) -> Result<u32, String> {
+    manager.pid().ok_or_else(|| "Sidecar not ready".to_string())
+}
+
+#[tauri::command]
+pub fn start_sidecar(app_handle: AppHandle, manager: State<'_, SidecarManager>) -> Result<(), String> {
+    manager.start(&app_handle)
+}
+
+#[tauri::command]
+pub fn stop_sidecar(manager: State<'_, SidecarManager>) -> Result<(), String> {
+    manager.stop()
 }
 
 #[tauri::command]
-pub fn get_sidecar_port(state: State<'_, Mutex<SidecarState>>) -> Result<u16, String> {
-    let state = state.lock().map_err(|e| e.to_string())?;
-    state.port.ok_or_else(|| "Sidecar not ready".to_string())
+pub fn restart_sidecar(app_handle: AppHandle, manager: State<'_, SidecarManager>) -> Result<(), String> {
+    manager.restart(&app_handle)
 }
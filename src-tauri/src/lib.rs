@@ -1,10 +1,10 @@
 mod commands;
 
-use commands::{get_sidecar_port, SidecarState};
-use std::io::{BufRead, BufReader};
-use std::process::{Child, Command, Stdio};
-use std::sync::Mutex;
-use tauri::Manager;
+use commands::{
+    get_sidecar_pid, get_sidecar_port, resolve_sidecar_config, restart_sidecar, start_sidecar,
+    stop_sidecar, SidecarManager,
+};
+use tauri::{Emitter, Manager};
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -16,63 +16,34 @@ pub fn run() {
                 let _ = window.set_focus();
             }
         }))
-        .manage(Mutex::new(SidecarState { port: None }))
-        .manage(Mutex::new(None::<Child>))
-        .invoke_handler(tauri::generate_handler![get_sidecar_port])
+        .invoke_handler(tauri::generate_handler![
+            get_sidecar_port,
+            get_sidecar_pid,
+            start_sidecar,
+            stop_sidecar,
+            restart_sidecar,
+        ])
         .setup(|app| {
             let app_handle = app.handle().clone();
 
-            // In dev mode, resolve the Python venv relative to the project root.
-            // Tauri runs from src-tauri/, so parent is the project root.
-            let project_root = std::env::current_dir()
-                .expect("Failed to get current directory")
-                .parent()
-                .expect("Failed to get project root")
-                .to_path_buf();
-
-            let python_path = project_root
-                .join("sidecar")
-                .join(".venv")
-                .join("bin")
-                .join("python3");
-
-            let sidecar_dir = project_root.join("sidecar");
-
-            // Spawn the Python sidecar process
-            let mut child = Command::new(&python_path)
-                .arg("-u") // unbuffered stdout
-                .arg("main.py")
-                .current_dir(&sidecar_dir)
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()
-                .expect("Failed to spawn Python sidecar");
-
-            // Read stdout in a background thread to capture the PORT line
-            let stdout = child.stdout.take().expect("Failed to capture stdout");
-
-            std::thread::spawn({
-                let handle = app_handle.clone();
-                move || {
-                    let reader = BufReader::new(stdout);
-                    for line in reader.lines() {
-                        if let Ok(line) = line {
-                            if let Some(port_str) = line.strip_prefix("PORT:") {
-                                if let Ok(port) = port_str.parse::<u16>() {
-                                    let state = handle.state::<Mutex<SidecarState>>();
-                                    let mut state = state.lock().unwrap();
-                                    state.port = Some(port);
-                                    eprintln!("Sidecar started on port {}", port);
-                                }
-                            }
-                        }
+            match resolve_sidecar_config(app) {
+                Ok(config) => {
+                    // Register before starting: the supervisor thread spawned by
+                    // `start` looks itself up via `handle.state::<SidecarManager>()`,
+                    // which panics if the type isn't managed yet.
+                    app.manage(SidecarManager::new(config));
+                    let manager = app.state::<SidecarManager>();
+                    if let Err(err) = manager.start(&app_handle) {
+                        eprintln!("Failed to start sidecar: {err}");
+                        let _ = app_handle.emit("sidecar://resolve-failed", err);
                     }
                 }
-            });
-
-            // Store child process for cleanup on exit
-            let child_state = app_handle.state::<Mutex<Option<Child>>>();
-            *child_state.lock().unwrap() = Some(child);
+                Err(err) => {
+                    eprintln!("Failed to resolve sidecar: {err}");
+                    let _ = app_handle.emit("sidecar://resolve-failed", err);
+                    app.manage(SidecarManager::unresolved());
+                }
+            }
 
             Ok(())
         })
@@ -81,15 +52,8 @@ pub fn run() {
 
     app.run(|app_handle, event| {
         if let tauri::RunEvent::Exit = event {
-            let state = app_handle.state::<Mutex<Option<Child>>>();
-            let mut guard = match state.lock() {
-                Ok(g) => g,
-                Err(_) => return,
-            };
-            if let Some(ref mut child) = *guard {
-                let _ = child.kill();
-                eprintln!("Sidecar process killed");
-            }
+            let manager = app_handle.state::<SidecarManager>();
+            manager.shutdown();
         }
     });
 }